@@ -14,18 +14,30 @@ use log::error;
 use log::info;
 use log::warn;
 use serde::Deserialize;
+use std::borrow::Cow;
 use std::env::var;
 use std::env::VarError;
 use std::process::exit;
 use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::Duration;
 
 static SPLIT_POINT: OnceLock<Regex> = OnceLock::new();
-
-fn parse_to_html(input: &str) -> String {
-    // html parsing is disabled for now
-    input.to_string()
-
-    // comrak::markdown_to_html(input, &comrak_options())
+static URL_PATTERN: OnceLock<Regex> = OnceLock::new();
+static MENTION_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+/// Mastodon counts every URL as exactly this many characters, regardless of its real length.
+const MASTODON_URL_LENGTH: usize = 23;
+
+/// Render `input` according to the post's chosen `format`. Only `Html` posts are actually rendered
+/// to HTML here; `Plain` and `Markdown` posts are sent to the instance as raw source text, since
+/// Mastodon (and Pleroma/Glitch) re-render `text/markdown` statuses server-side themselves, and
+/// sending pre-rendered HTML under that content type just gets double-escaped.
+fn parse_to_html(input: &str, format: PostFormat) -> String {
+    match format {
+        PostFormat::Plain | PostFormat::Markdown => input.to_string(),
+        PostFormat::Html => comrak::markdown_to_html(input, &comrak_options()),
+    }
 }
 
 fn comrak_options() -> Options {
@@ -45,11 +57,58 @@ fn comrak_options() -> Options {
     conversion_options
 }
 
+/// How a post's body is rendered and which content type it is sent to the instance as.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum PostFormat {
+    /// Send the raw source text as-is.
+    #[default]
+    Plain,
+    /// Send the raw Markdown source as `text/markdown`; the instance renders it server-side.
+    Markdown,
+    /// Render the source as HTML locally and send it as `text/html`.
+    Html,
+}
+
+impl PostFormat {
+    /// The Mastodon status content type to send this format as.
+    fn content_type(self) -> &'static str {
+        match self {
+            PostFormat::Plain => "text/plain",
+            PostFormat::Markdown => "text/markdown",
+            PostFormat::Html => "text/html",
+        }
+    }
+}
+
 /// User-definable options for a post, specified in the frontmatter.
 #[derive(Deserialize, Default, Debug)]
 struct PostOptions {
     #[serde(default, rename = "cn")]
     content_notice: Option<String>,
+    #[serde(default)]
+    format: PostFormat,
+    /// Hashtags (without the leading `#`) appended to every post in the series so each one stays
+    /// discoverable on its own. Falls back to a trailing `#a #b #c` line in the body if empty.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Media (images/video) to upload and attach to the thread.
+    #[serde(default)]
+    media: Vec<MediaAttachment>,
+}
+
+/// A single media attachment to upload and attach to a post in the series.
+#[derive(Deserialize, Debug, Clone)]
+struct MediaAttachment {
+    /// Path to the media file to upload, relative to the current working directory.
+    path: String,
+    /// Accessible description of the media. Required so authors never forget one, even though
+    /// elefren 0.22's upload call has no way to actually send it to the instance (see
+    /// `upload_media`); kept as a future-proofing field and a forcing function in the meantime.
+    alt: Option<String>,
+    /// Index (0-based) of the post in the series to attach this media to. Defaults to the first.
+    #[serde(default)]
+    attach_to: usize,
 }
 
 fn extract_options_from_frontmatter(input: &str) -> PostOptions {
@@ -92,6 +151,50 @@ fn remove_frontmatter(input: &str) -> String {
     String::from_utf8_lossy(&output).replace("\\!", "!")
 }
 
+/// Detect a trailing line made up purely of hashtags (e.g. `#rust #blog`), like the group-actor
+/// bot workaround, and split it off from the body. Returns the remaining body and the detected
+/// tags (without their leading `#`), or the body unchanged and no tags if the last line isn't one.
+fn extract_trailing_hashtags(text: &str) -> (String, Vec<String>) {
+    let trimmed = text.trim_end();
+    let Some(last_newline) = trimmed.rfind('\n') else {
+        return (text.to_string(), Vec::new());
+    };
+    let (body, last_line) = trimmed.split_at(last_newline);
+    let words = last_line.split_whitespace().collect::<Vec<_>>();
+    if !words.is_empty() && words.iter().all(|word| word.len() > 1 && word.starts_with('#')) {
+        let tags = words
+            .iter()
+            .map(|word| word.trim_start_matches('#').to_string())
+            .collect();
+        (body.trim_end().to_string(), tags)
+    } else {
+        (text.to_string(), Vec::new())
+    }
+}
+
+/// Build the trailing hashtag footer appended to every post in the series so each one remains
+/// discoverable on its own, even when later posts are unlisted.
+fn build_hashtag_footer(tags: &[String]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+    let hashtags = tags
+        .iter()
+        .map(|tag| format!("#{}", tag))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("\n\n{}", hashtags)
+}
+
+/// Whether `text` ends with a hashtag, i.e. whether it needs the Pleroma trailing-hashtag
+/// workaround below.
+fn ends_with_hashtag(text: &str) -> bool {
+    text.trim_end_matches('\u{200B}')
+        .rsplit(char::is_whitespace)
+        .next()
+        .is_some_and(|word| word.starts_with('#'))
+}
+
 /// Split a piece of text at given indices.
 fn split_at_indices<'a>(input: &'a str, split_points: &'_ [usize]) -> Vec<&'a str> {
     let mut current_start = 0;
@@ -109,45 +212,254 @@ fn split_at_indices<'a>(input: &'a str, split_points: &'_ [usize]) -> Vec<&'a st
     elements
 }
 
+/// A byte range of a markdown node that would be broken if a post were split in the middle of it,
+/// e.g. a fenced/indented code block, a link or image, a table row, a list item, or a blockquote.
+#[derive(Debug, Clone, Copy)]
+struct AtomicRange {
+    start: usize,
+    end: usize,
+    /// Code blocks can't be split at all without corrupting them, unlike the other atomic kinds
+    /// which can still fall back to a plain regex split if they're too big on their own.
+    is_code_block: bool,
+}
+
+/// Byte offset of the start of each line in `input`, so that comrak's line/column source
+/// positions can be translated back into the byte indices `split_text` works with.
+fn line_starts(input: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+    starts
+}
+
+fn sourcepos_to_byte(line_starts: &[usize], pos: comrak::nodes::LineColumn) -> usize {
+    line_starts.get(pos.line.saturating_sub(1)).copied().unwrap_or(0) + pos.column.saturating_sub(1)
+}
+
+/// Walk the comrak AST to find the byte ranges of nodes that must not be split in their middle.
+fn atomic_ranges(input: &str) -> Vec<AtomicRange> {
+    let arena = Arena::new();
+    let document = comrak::parse_document(&arena, input, &comrak_options());
+    let starts = line_starts(input);
+    document
+        .descendants()
+        .filter_map(|element| {
+            let data = element.data.borrow();
+            let is_code_block = matches!(data.value, comrak::nodes::NodeValue::CodeBlock(_));
+            let is_atomic = is_code_block
+                || matches!(
+                    data.value,
+                    comrak::nodes::NodeValue::Link(_)
+                        | comrak::nodes::NodeValue::Image(_)
+                        | comrak::nodes::NodeValue::Table(_)
+                        | comrak::nodes::NodeValue::TableRow(_)
+                        | comrak::nodes::NodeValue::Item(_)
+                        | comrak::nodes::NodeValue::BlockQuote
+                );
+            is_atomic.then(|| AtomicRange {
+                start: sourcepos_to_byte(&starts, data.sourcepos.start),
+                // comrak's end position is inclusive of the node's last byte; make it exclusive
+                // so range comparisons below treat that last byte as still "inside" the node.
+                end: sourcepos_to_byte(&starts, data.sourcepos.end) + 1,
+                is_code_block,
+            })
+        })
+        .collect()
+}
+
+/// Promote a split point that falls inside an atomic range to that range's end, so the split
+/// instead happens at the nearest enclosing block boundary.
+fn promote_split_point(point: usize, ranges: &[AtomicRange]) -> usize {
+    ranges
+        .iter()
+        .find(|range| point > range.start && point < range.end)
+        .map_or(point, |range| range.end)
+}
+
+/// Whether `snippet` (a sub-slice of `input`) lies entirely within a code block of `input`.
+fn is_code_block(snippet: &str, input: &str, ranges: &[AtomicRange]) -> bool {
+    let start = snippet.as_ptr() as usize - input.as_ptr() as usize;
+    let end = start + snippet.len();
+    ranges
+        .iter()
+        .any(|range| range.is_code_block && range.start <= start && range.end >= end)
+}
+
+/// Count a post the way Mastodon does: Unicode scalar values rather than UTF-8 bytes, every URL
+/// counted as a fixed `MASTODON_URL_LENGTH` regardless of its real length, and `@user@domain`
+/// mentions counted as just `@user` since the `@domain` part is free.
+fn count_mastodon_length(text: &str) -> usize {
+    let url_regex = URL_PATTERN.get_or_init(|| Regex::new(r"https?://\S+").unwrap());
+    let mention_regex = MENTION_PATTERN.get_or_init(|| Regex::new(r"@[[:word:].-]+(@[[:word:].-]+)").unwrap());
+
+    let mut length = text.chars().count() as isize;
+
+    for url_match in url_regex.find_iter(text) {
+        let url_match = url_match.expect("error while counting URLs");
+        length -= url_match.as_str().chars().count() as isize;
+        length += MASTODON_URL_LENGTH as isize;
+    }
+
+    for mention_match in mention_regex.captures_iter(text) {
+        let mention_match = mention_match.expect("error while counting mentions");
+        if let Some(domain) = mention_match.get(1) {
+            length -= domain.as_str().chars().count() as isize;
+        }
+    }
+
+    length.max(0) as usize
+}
+
+static TAG_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+/// Strip HTML tags from rendered markup, leaving only the visible text. Used so length checks
+/// count what Mastodon actually displays, not raw `<p>`/`<a href="...">` markup.
+fn strip_html_tags(html: &str) -> String {
+    let tag_regex = TAG_PATTERN.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+    let mut stripped = String::with_capacity(html.len());
+    let mut last_end = 0;
+    for tag_match in tag_regex.find_iter(html) {
+        let tag_match = tag_match.expect("error while stripping HTML tags");
+        stripped.push_str(&html[last_end..tag_match.start()]);
+        last_end = tag_match.end();
+    }
+    stripped.push_str(&html[last_end..]);
+    stripped
+}
+
+/// The Mastodon-counted length of a post body. HTML posts are rendered and stripped of markup
+/// first, since the raw HTML tags Mastodon never displays would otherwise be counted as real
+/// characters; plain and Markdown posts are sent (and so counted) as raw source text.
+fn visible_length(text: &str, format: PostFormat) -> usize {
+    let rendered = parse_to_html(text, format);
+    let visible = match format {
+        PostFormat::Plain | PostFormat::Markdown => rendered,
+        PostFormat::Html => strip_html_tags(&rendered),
+    };
+    count_mastodon_length(&visible)
+}
+
+/// Whether `text` by itself, without any numbering suffix, already exceeds the character limit.
+fn exceeds_limit(text: &str, character_limit: usize, format: PostFormat) -> bool {
+    visible_length(text, format) > character_limit
+}
+
 fn is_under_post_limit(
     text: &str,
     post_number: usize,
     post_count: usize,
     character_limit: usize,
+    format: PostFormat,
 ) -> bool {
     let post_count_length = (post_count as f64).log10().ceil() as usize;
     let post_number_length = (post_number as f64).log10().ceil() as usize;
-    let post_length = parse_to_html(text).len();
+    // Count what the instance counts against the limit: the rendered, tag-stripped text for HTML
+    // posts, the raw source for plain and Markdown posts.
+    let post_length = visible_length(text, format);
     // 4 for the space, two braces, and slash
     post_length + post_count_length + post_number_length + 4 <= character_limit
 }
 
 /// Split blog post into lists of posts that observe the character limit.
-fn split_text(input: &str, character_limit: usize) -> Vec<String> {
+fn split_text(input: &str, character_limit: usize, format: PostFormat) -> Vec<String> {
     let input = input.replace('\r', "");
 
     let expected_post_count =
-        ((parse_to_html(&input).len() / character_limit) as f64 * 1.5).ceil() as usize;
+        ((visible_length(&input, format) / character_limit) as f64 * 1.5).ceil() as usize;
     debug!("Expect to create {} posts.", expected_post_count);
 
     let regex_text = "(?m:(?:\\.[ \t]+(?!\n))|(?:\n *\n))";
     let split_regex = SPLIT_POINT.get_or_init(|| Regex::new(&regex_text).unwrap());
 
-    let split_points = split_regex
+    // Record which byte ranges must not be cut in the middle (code blocks, links, tables, lists,
+    // blockquotes), then promote every naive regex split point out of those ranges.
+    let ranges = atomic_ranges(&input);
+    let mut split_points = split_regex
         .find_iter(&input)
         .map(|m| m.expect("error while splitting text").end())
+        .map(|point| promote_split_point(point, &ranges))
         .collect::<Vec<_>>();
+    split_points.dedup();
 
     let minimal_text_segments = split_at_indices(&input, &split_points).into_iter();
     let mut text_segments = Vec::new();
     let mut current_segment = String::new();
     for snippet_ref in minimal_text_segments {
+        // A minimal segment this large can still be a merger of several atomic ranges glued
+        // together with no blank line between them (e.g. a paragraph immediately followed by a
+        // fenced code block) rather than a single protected block that's oversized on its own. So
+        // rather than only checking whether the *whole* snippet sits inside one atomic range,
+        // also split it further at the boundary of every atomic range it merely overlaps: that
+        // boundary is always a safe place to cut, blank line or not.
+        if exceeds_limit(snippet_ref, character_limit, format) {
+            let snippet_start = snippet_ref.as_ptr() as usize - input.as_ptr() as usize;
+            let snippet_end = snippet_start + snippet_ref.len();
+            // Code blocks and atomic ranges that fit a post stay protected; a non-code atomic
+            // range too big on its own is no longer protected, so the regex split falls back
+            // to cutting inside it.
+            let protected_ranges = ranges
+                .iter()
+                .copied()
+                .filter(|range| {
+                    range.is_code_block
+                        || input.get(range.start..range.end).map_or(true, |text| {
+                            !exceeds_limit(text, character_limit, format)
+                        })
+                })
+                .collect::<Vec<_>>();
+            let mut fallback_points = split_regex
+                .find_iter(snippet_ref)
+                .map(|m| snippet_start + m.expect("error while splitting text").end())
+                .map(|point| promote_split_point(point, &protected_ranges))
+                .collect::<Vec<_>>();
+            fallback_points.extend(
+                ranges
+                    .iter()
+                    .flat_map(|range| [range.start, range.end])
+                    .filter(|&point| point > snippet_start && point < snippet_end)
+                    .map(|point| promote_split_point(point, &protected_ranges)),
+            );
+            fallback_points.sort_unstable();
+            fallback_points.dedup();
+            let fallback_points = fallback_points
+                .into_iter()
+                .map(|point| point - snippet_start)
+                .collect::<Vec<_>>();
+
+            for fallback_snippet in split_at_indices(snippet_ref, &fallback_points) {
+                // Now that merged ranges have been split apart above, a fallback snippet that's
+                // still a code block is really a whole code block on its own, too big to split.
+                if is_code_block(fallback_snippet, &input, &ranges)
+                    && exceeds_limit(fallback_snippet, character_limit, format)
+                {
+                    panic!(
+                        "A fenced or indented code block is larger than the character limit ({} characters) and cannot be split without breaking it.",
+                        character_limit
+                    );
+                }
+                let expanded_segment = current_segment.clone() + fallback_snippet;
+                if is_under_post_limit(
+                    &expanded_segment,
+                    text_segments.len() + 1,
+                    expected_post_count,
+                    character_limit,
+                    format,
+                ) {
+                    current_segment = expanded_segment;
+                } else {
+                    text_segments.push(current_segment);
+                    current_segment = fallback_snippet.to_string();
+                }
+            }
+            continue;
+        }
+
         let expanded_segment = current_segment.clone() + snippet_ref;
         if is_under_post_limit(
             &expanded_segment,
             text_segments.len() + 1,
             expected_post_count,
             character_limit,
+            format,
         ) {
             // We can add this text snippet to the current one.
             current_segment = expanded_segment;
@@ -166,7 +478,10 @@ fn split_text(input: &str, character_limit: usize) -> Vec<String> {
         .into_iter()
         .enumerate()
         .map(|(index, segment)| {
-            parse_to_html(&format!("{} ({}/{})", segment, index + 1, post_count))
+            // Append the counter after rendering, so it can't be swallowed by an unclosed HTML
+            // element spanning a split boundary.
+            let rendered = parse_to_html(&segment, format);
+            format!("{} ({}/{})", rendered, index + 1, post_count)
         })
         .collect()
 }
@@ -183,10 +498,14 @@ fn create_client() -> Result<Mastodon, VarError> {
     }))
 }
 
-/// RAII guard that will delete all posts created when an error occurs later on.
+/// RAII guard that will delete all posts created when an error occurs later on. Mastodon (and
+/// elefren) have no endpoint to delete a media upload that was never attached to a status, so any
+/// such orphans can only be reported, not cleaned up automatically; see the `Drop` impl below.
 struct PostDeleter<'c> {
     /// List of post IDs
     posts: Vec<String>,
+    /// List of uploaded media attachment IDs that haven't been attached to a surviving post yet.
+    media: Vec<String>,
     client: &'c Mastodon,
     is_armed: bool,
 }
@@ -195,6 +514,7 @@ impl<'c> PostDeleter<'c> {
     pub fn new(client: &'c Mastodon) -> Self {
         Self {
             posts: Vec::new(),
+            media: Vec::new(),
             is_armed: true,
             client,
         }
@@ -204,6 +524,10 @@ impl<'c> PostDeleter<'c> {
         self.posts.push(id.into());
     }
 
+    pub fn add_media(&mut self, id: &str) {
+        self.media.push(id.into());
+    }
+
     pub fn disarm(&mut self) {
         self.is_armed = false;
     }
@@ -222,6 +546,169 @@ impl<'c> Drop for PostDeleter<'c> {
                 _ => {}
             }
         }
+        if !self.media.is_empty() {
+            // Neither Mastodon's API nor elefren expose a way to delete a media attachment that
+            // was never attached to a status, so the best we can do is tell the user which ones
+            // were orphaned; the instance will eventually garbage-collect them on its own.
+            warn!(
+                "{} uploaded media attachment(s) were never attached to a post and can't be \
+                 deleted automatically; the instance will garbage-collect them eventually: {:?}",
+                self.media.len(),
+                self.media
+            );
+        }
+    }
+}
+
+/// Mastodon instances only accept up to this many media attachments per status.
+const MAX_MEDIA_PER_POST: usize = 4;
+
+/// Errors that can happen while posting a series: either the instance rejects something, or the
+/// post's own configuration turns out to be invalid once we know how many posts there are.
+#[derive(Debug)]
+enum PostError {
+    Mastodon(elefren::Error),
+    /// A `media` entry is missing its required `alt` text.
+    MissingAltText(String),
+    /// A `media` entry's `attach_to` doesn't refer to any post in the series.
+    InvalidAttachTo { attach_to: usize, post_count: usize },
+}
+
+impl From<elefren::Error> for PostError {
+    fn from(error: elefren::Error) -> Self {
+        PostError::Mastodon(error)
+    }
+}
+
+impl std::fmt::Display for PostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PostError::Mastodon(error) => write!(f, "{:?}", error),
+            PostError::MissingAltText(path) => {
+                write!(f, "media attachment {:?} is missing required alt text", path)
+            }
+            PostError::InvalidAttachTo {
+                attach_to,
+                post_count,
+            } => write!(
+                f,
+                "media attach_to index {} doesn't match any of the {} posts in the series",
+                attach_to, post_count
+            ),
+        }
+    }
+}
+
+/// Upload every configured media attachment and return, for each, which post in the series it
+/// should be attached to and the attachment ID the instance gave back.
+///
+/// On a validation failure partway through, returns an error instead of aborting the process
+/// outright, so the caller's `PostDeleter` still runs and reports the media already uploaded in
+/// this call as orphaned (it can't delete them, see `PostDeleter`'s `Drop` impl).
+///
+/// elefren 0.22's `Mastodon::media` only takes a file path and has no parameter for a
+/// description/alt text at all, so the required `alt` field can't actually be transmitted to the
+/// instance through this client; it's still required below so the attachment at least gets
+/// flagged here instead of silently shipping with no alt text and nobody noticing.
+fn upload_media(
+    client: &Mastodon,
+    media: &[MediaAttachment],
+    deleter: &mut PostDeleter,
+) -> Result<Vec<(usize, String)>, PostError> {
+    let mut uploaded = Vec::new();
+    for attachment in media {
+        if attachment.alt.is_none() {
+            return Err(PostError::MissingAltText(attachment.path.clone()));
+        }
+        let uploaded_attachment = client.media(Cow::Owned(attachment.path.clone()))?;
+        deleter.add_media(&uploaded_attachment.id);
+        uploaded.push((attachment.attach_to, uploaded_attachment.id));
+    }
+    Ok(uploaded)
+}
+
+/// Starting delay for the exponential backoff used when retrying a rate-limited or transiently
+/// failed post; doubled on every further attempt, capped at `RETRY_MAX_DELAY`.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+/// Upper bound on the backoff delay between retries, so a misbehaving instance can't stall the
+/// whole thread indefinitely.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+/// Default for the `max_retries` environment variable, if unset.
+const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Whether an elefren error is an actual 429/rate-limit response from the instance. elefren
+/// doesn't surface the raw HTTP status code or the `X-RateLimit-*` response headers to callers,
+/// only the decoded API error body, so this is the best signal available here.
+fn is_rate_limited(error: &elefren::Error) -> bool {
+    match error {
+        elefren::Error::Api(api_error) => {
+            let message = format!("{:?}", api_error).to_lowercase();
+            message.contains("429")
+                || message.contains("rate limit")
+                || message.contains("too many requests")
+        }
+        _ => false,
+    }
+}
+
+/// Whether an elefren error is a transient network failure (timed out, couldn't connect), as
+/// opposed to a permanent one (bad credentials, a rejected/invalid status). elefren never calls
+/// `error_for_status` on its responses, so its `reqwest::Error`s never carry a status code and
+/// `is_server_error`/`is_client_error` can never be true here; the low-level transport failures
+/// (connection refused/reset, DNS, TLS) surface as `is_http` instead, so that's what we check
+/// alongside `is_timeout`.
+fn is_transient_network_error(error: &elefren::Error) -> bool {
+    match error {
+        elefren::Error::Http(reqwest_error) => {
+            reqwest_error.is_timeout() || reqwest_error.is_http()
+        }
+        elefren::Error::Io(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether `error` is worth retrying: an actual rate limit or a transient network failure, as
+/// opposed to a permanent failure like bad credentials or a validation/content-policy rejection
+/// that retrying would never fix.
+fn is_retryable(error: &elefren::Error) -> bool {
+    is_rate_limited(error) || is_transient_network_error(error)
+}
+
+/// Delay before the next retry. We would prefer to sleep until the instance's `X-RateLimit-Reset`
+/// time, but elefren doesn't expose response headers through its client, so we fall back to
+/// capped exponential backoff regardless of whether the error was a rate limit or a network blip.
+fn backoff_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(RETRY_MAX_DELAY)
+}
+
+/// Create a status, retrying on rate limiting or other transient errors up to `max_retries`
+/// times before giving up and returning the error (on which the armed `PostDeleter` deletes the
+/// whole thread).
+fn new_status_with_retry(
+    client: &Mastodon,
+    status: &elefren::status_builder::NewStatus,
+    max_retries: u32,
+) -> Result<elefren::entities::status::Status, elefren::Error> {
+    let mut attempt = 0;
+    loop {
+        match client.new_status(status.clone()) {
+            Ok(created) => return Ok(created),
+            Err(why) if attempt < max_retries && is_retryable(&why) => {
+                let delay = backoff_delay(attempt);
+                warn!(
+                    "Rate limited or transient error creating post (attempt {}/{}): {:?}. Retrying in {:?}.",
+                    attempt + 1,
+                    max_retries,
+                    why,
+                    delay
+                );
+                sleep(delay);
+                attempt += 1;
+            }
+            Err(why) => return Err(why),
+        }
     }
 }
 
@@ -229,29 +716,76 @@ fn post_series(
     client: &Mastodon,
     posts: &[String],
     options: &PostOptions,
-) -> Result<(), elefren::Error> {
+    hashtag_footer: &str,
+    max_retries: u32,
+    post_delay: Option<Duration>,
+) -> Result<(), PostError> {
     let mut deleter = PostDeleter::new(client);
 
+    // Check every attach_to up front, before uploading anything: the post count is only known
+    // after splitting, so a stale or typo'd index can't be caught any earlier than this.
+    for attachment in &options.media {
+        if attachment.attach_to >= posts.len() {
+            return Err(PostError::InvalidAttachTo {
+                attach_to: attachment.attach_to,
+                post_count: posts.len(),
+            });
+        }
+    }
+
+    let media_by_post = upload_media(client, &options.media, &mut deleter)?;
+
     let mut last_status = None;
-    for post in posts {
+    for (index, post) in posts.iter().enumerate() {
+        if index > 0 {
+            if let Some(post_delay) = post_delay {
+                sleep(post_delay);
+            }
+        }
+        let mut post = post.clone() + hashtag_footer;
+        // Pleroma and some other instances mangle a status' final hashtag; a trailing zero-width
+        // space keeps it intact without being visible.
+        if ends_with_hashtag(&post) {
+            post.push('\u{200B}');
+        }
         let mut status = StatusBuilder::new();
         status
-            .status(post)
+            .status(&post)
             .language(elefren::Language::Eng)
             .visibility(if last_status.is_none() {
                 elefren::status_builder::Visibility::Public
             } else {
                 elefren::status_builder::Visibility::Unlisted
             })
-            .content_type("text/plain");
+            .content_type(options.format.content_type());
         if let Some(previous_status) = last_status {
             status.in_reply_to(previous_status);
         }
         if let Some(content_notice) = &options.content_notice {
             status.spoiler_text(content_notice);
         }
-        let status = client.new_status(status.build()?)?;
+        let mut media_for_post = media_by_post
+            .iter()
+            .filter(|(attach_to, _)| *attach_to == index)
+            .map(|(_, id)| id.clone());
+        let media_ids = media_for_post
+            .by_ref()
+            .take(MAX_MEDIA_PER_POST)
+            .collect::<Vec<_>>();
+        if media_for_post.next().is_some() {
+            warn!(
+                "Post {} has more than {} media attachments; only the first {} were attached.",
+                index + 1,
+                MAX_MEDIA_PER_POST,
+                MAX_MEDIA_PER_POST
+            );
+        }
+        if !media_ids.is_empty() {
+            status.media_ids(&media_ids);
+        }
+        let status = new_status_with_retry(client, &status.build()?, max_retries)?;
         deleter.add_post(&status.id);
+        deleter.media.retain(|id| !media_ids.contains(id));
         last_status = Some(status.id);
         info!("Post created: {}", status.uri);
     }
@@ -267,6 +801,21 @@ fn main() {
         .expect("character limit environment variable not defined")
         .parse::<usize>()
         .expect("character limit environment variable is not an integer");
+    let max_retries = var("max_retries")
+        .ok()
+        .map(|value| {
+            value
+                .parse::<u32>()
+                .expect("max_retries environment variable is not an integer")
+        })
+        .unwrap_or(DEFAULT_MAX_RETRIES);
+    let post_delay = var("post_delay_seconds").ok().map(|value| {
+        Duration::from_secs(
+            value
+                .parse::<u64>()
+                .expect("post_delay_seconds environment variable is not an integer"),
+        )
+    });
 
     let post_file = std::env::args()
         .nth(1)
@@ -275,25 +824,137 @@ fn main() {
     let post_options = extract_options_from_frontmatter(&post_md_text);
     info!("Post options: {:#?}", post_options);
     let post_md_text = remove_frontmatter(&post_md_text);
-
-    // Take CN into account by subtracting it from the actual character limit.
+    let (post_md_text, tags) = if post_options.tags.is_empty() {
+        extract_trailing_hashtags(&post_md_text)
+    } else {
+        (post_md_text, post_options.tags.clone())
+    };
+    let hashtag_footer = build_hashtag_footer(&tags);
+
+    // Take the CN and hashtag footer into account by subtracting them from the actual character
+    // limit, since both are added back onto every post after splitting.
     let character_limit = character_limit
         - post_options
             .content_notice
             .as_ref()
-            .map_or(0, |cn| cn.len());
+            .map_or(0, |cn| count_mastodon_length(cn))
+        - count_mastodon_length(&hashtag_footer);
 
-    let text_sections = split_text(&post_md_text, character_limit);
+    let text_sections = split_text(&post_md_text, character_limit, post_options.format);
     debug!(
         "Post lengths: {:?}",
         text_sections.iter().map(|t| t.len()).collect::<Vec<_>>()
     );
 
-    if text_sections.iter().any(|t| t.len() > character_limit) {
+    // text_sections are already rendered (for HTML posts); strip any markup before counting, same
+    // as exceeds_limit does, so raw tags aren't mistaken for real characters and plain/Markdown
+    // text that merely looks like a tag (e.g. `Vec<String>`) isn't mistakenly stripped.
+    if text_sections.iter().any(|t| {
+        let visible = match post_options.format {
+            PostFormat::Plain | PostFormat::Markdown => Cow::Borrowed(t.as_str()),
+            PostFormat::Html => Cow::Owned(strip_html_tags(t)),
+        };
+        count_mastodon_length(&visible) > character_limit
+    }) {
         error!("At least one text section is over the character limit, aborting.");
         exit(1);
     }
 
     let client = create_client().expect("couldn't connect to instance");
-    post_series(&client, &text_sections, &post_options).expect("posting failed");
+    post_series(
+        &client,
+        &text_sections,
+        &post_options,
+        &hashtag_footer,
+        max_retries,
+        post_delay,
+    )
+    .expect("posting failed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_mastodon_length_counts_urls_as_fixed_length() {
+        let prefix = "check this out: ";
+        let text = format!("{}https://example.com/a/very/long/path/that/would/otherwise/count/a/lot", prefix);
+        assert_eq!(
+            count_mastodon_length(&text),
+            prefix.chars().count() + MASTODON_URL_LENGTH
+        );
+    }
+
+    #[test]
+    fn count_mastodon_length_ignores_mention_domain() {
+        assert_eq!(count_mastodon_length("hello @user@example.com!"), "hello @user!".chars().count());
+    }
+
+    #[test]
+    fn count_mastodon_length_counts_code_points_not_bytes() {
+        // "café" is 4 code points but 5 UTF-8 bytes.
+        assert_eq!(count_mastodon_length("café"), 4);
+    }
+
+    #[test]
+    fn extract_trailing_hashtags_splits_off_hashtag_line() {
+        let (body, tags) = extract_trailing_hashtags("Some post text.\n\n#rust #blog");
+        assert_eq!(body, "Some post text.");
+        assert_eq!(tags, vec!["rust".to_string(), "blog".to_string()]);
+    }
+
+    #[test]
+    fn extract_trailing_hashtags_leaves_non_hashtag_line_untouched() {
+        let text = "Some post text.\n\nNot a hashtag line.";
+        let (body, tags) = extract_trailing_hashtags(text);
+        assert_eq!(body, text);
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn atomic_ranges_finds_code_block() {
+        let input = "Intro.\n\n```\ncode\n```\n";
+        let ranges = atomic_ranges(input);
+        assert!(ranges.iter().any(|range| range.is_code_block));
+    }
+
+    #[test]
+    fn promote_split_point_moves_out_of_atomic_range() {
+        let ranges = vec![AtomicRange {
+            start: 5,
+            end: 10,
+            is_code_block: true,
+        }];
+        assert_eq!(promote_split_point(7, &ranges), 10);
+        assert_eq!(promote_split_point(12, &ranges), 12);
+    }
+
+    #[test]
+    fn split_text_keeps_code_block_whole_when_merged_with_preceding_paragraph() {
+        // No blank line between the paragraph and the fence (valid CommonMark), and a blank line
+        // inside the code block itself, reproducing the conditions that used to let the fallback
+        // regex split split inside the fence.
+        let intro = "Intro paragraph that runs on for a little while right here.";
+        let code = "```\nfirst code line\n\nsecond code line\nthird code line.\n```";
+        let input = format!("{}\n{}\n", intro, code);
+        // Large enough to hold the code block whole, too small to hold it merged with the intro.
+        let character_limit = visible_length(code, PostFormat::Plain) + 5;
+        assert!(visible_length(&input, PostFormat::Plain) > character_limit);
+
+        let posts = split_text(&input, character_limit, PostFormat::Plain);
+
+        let code_post = posts
+            .iter()
+            .find(|post| post.contains("first code line"))
+            .expect("a post should contain the code block");
+        assert!(code_post.contains("second code line"));
+        assert!(code_post.contains("third code line"));
+    }
+
+    #[test]
+    fn is_retryable_treats_io_errors_as_transient() {
+        let error = elefren::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, "boom"));
+        assert!(is_retryable(&error));
+    }
 }